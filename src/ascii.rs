@@ -1,67 +1,82 @@
 #![allow(dead_code)]
-#![allow(clippy::transmute_int_to_char)]
 //! A character-oriented decoder implementation that will take an underlying [std::u8] (byte) source
 //! and produce a stream of decoded ASCII characters
-use std::io::BufRead;
-use std::mem::transmute;
 
 use crate::common::*;
 use crate::decoder_error;
 
-/// An ASCIIdecoder, which takes a ref to a [BufRead] instance.
-pub struct AsciiDecoder<'a, B: BufRead> {
+/// The maximum number of characters a caller may match in a single [AsciiDecoder::try_read_string]
+/// call, and the most that [AsciiDecoder::peek] / `try_read_string` will ever buffer ahead. Fixed
+/// by the inline [LOOKAHEAD_CAPACITY] buffer so that no heap allocation is required.
+pub const MAX_LOOKAHEAD: usize = LOOKAHEAD_CAPACITY;
+
+/// An ASCIIdecoder, which takes a ref to a [ByteSource] instance. Bytes are pulled from the
+/// underlying source one at a time via [ByteSource::source_fill_buf]/[ByteSource::source_consume],
+/// so peak memory is bounded by the source buffer regardless of the size of the input.
+pub struct AsciiDecoder<'a, B: ByteSource> {
     /// The input stream
     input: &'a mut B,
-    /// Staging buffer
-    buffer: Vec<u8>,
-    /// Initialisation flag
-    init: bool,
     /// The current index into the input
     index: usize,
+    /// Characters decoded ahead of the read position by [AsciiDecoder::peek] /
+    /// [AsciiDecoder::try_read_string], or pending re-delivery after a [AsciiDecoder::rewind]
+    lookahead: InlineChars,
+    /// Whether a mark is currently active
+    marked: bool,
+    /// Characters delivered since the active mark, retained so that [AsciiDecoder::rewind] can
+    /// re-deliver them without re-reading the underlying source
+    history: InlineChars,
 }
 
-impl<'a, Buffer: BufRead> AsciiDecoder<'a, Buffer> {
+impl<'a, Buffer: ByteSource> AsciiDecoder<'a, Buffer> {
     /// Create a new decoder with a default buffer size
     pub fn new(r: &'a mut Buffer) -> Self {
         AsciiDecoder {
             input: r,
-            buffer: vec![],
-            init: false,
             index: 0,
+            lookahead: InlineChars::new(),
+            marked: false,
+            history: InlineChars::new(),
         }
     }
 
-    /// Initialise and read the input into an internal buffer for decoding
-    fn init(&mut self) -> DecoderResult<()> {
-        match self.input.read_to_end(&mut self.buffer) {
-            Ok(_) => {
-                self.init = true;
-                Ok(())
+    /// Attempt to decode the next character in the underlying stream. Exactly one byte is
+    /// consumed per successful decode; a non-ASCII byte is left in place so that the position of
+    /// the failure is preserved. Characters buffered by a peek, a `try_read_string` or a rewind
+    /// are returned first.
+    fn decode_next(&mut self) -> DecoderResult<char> {
+        if let Some(c) = self.lookahead.pop_front() {
+            if self.marked {
+                self.history.push_back(c);
             }
-            Err(_) => Err(decoder_error!(
-                DecoderErrorCode::StreamFailure,
-                "failed to read input"
-            )),
+            return Ok(c);
         }
-    }
-
-    /// Attempt to decode the next character in the underlying stream.
-    fn decode_next(&mut self) -> DecoderResult<char> {
-        if !self.init {
-            self.init()?;
+        let c = self.decode_scalar()?;
+        if self.marked {
+            self.history.push_back(c);
         }
+        Ok(c)
+    }
 
-        if self.index >= self.buffer.len() {
-            return Err(decoder_error!(
-                DecoderErrorCode::EndOfInput,
-                "end of input reached"
-            ));
-        }
+    /// Decode a single character directly from the underlying source, bypassing the re-delivery
+    /// buffer.
+    fn decode_scalar(&mut self) -> DecoderResult<char> {
+        let available = self.input.source_fill_buf()?;
+        let byte = match available.first() {
+            Some(b) => *b,
+            None => {
+                return Err(decoder_error!(
+                    DecoderErrorCode::EndOfInput,
+                    "end of input reached"
+                ))
+            }
+        };
 
-        match self.buffer[self.index] {
+        match byte {
             0x0..=0x7f => unsafe {
+                self.input.source_consume(1);
                 self.index += 1;
-                Ok(transmute(self.buffer[self.index - 1] as u32))
+                Ok(char::from_u32_unchecked(byte as u32))
             },
             _ => Err(decoder_error!(
                 DecoderErrorCode::OutOfRange,
@@ -69,20 +84,160 @@ impl<'a, Buffer: BufRead> AsciiDecoder<'a, Buffer> {
             )),
         }
     }
+
+    /// Return the next character without advancing the read position, or `None` at end-of-input
+    /// (or on a non-ASCII byte). Repeated calls return the same character until it is consumed via
+    /// [AsciiDecoder::decode_next].
+    pub fn peek(&mut self) -> Option<char> {
+        if self.lookahead.is_empty() {
+            match self.decode_scalar() {
+                Ok(c) => {
+                    self.lookahead.push_back(c);
+                }
+                Err(_) => return None,
+            }
+        }
+        self.lookahead.front()
+    }
+
+    /// Consume and return `true` if, and only if, the upcoming characters match `s` exactly; on a
+    /// mismatch the stream is left untouched. At most [MAX_LOOKAHEAD] characters may be matched in
+    /// a single call — a longer `s` (or one that cannot be fully read) simply returns `false`.
+    pub fn try_read_string(&mut self, s: &str) -> bool {
+        let needed = s.chars().count();
+        if needed == 0 {
+            return true;
+        }
+        if needed > MAX_LOOKAHEAD {
+            return false;
+        }
+        while self.lookahead.len() < needed {
+            match self.decode_scalar() {
+                Ok(c) => {
+                    self.lookahead.push_back(c);
+                }
+                Err(_) => return false,
+            }
+        }
+        let matches = s
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.lookahead.get(i) == Some(c));
+        if matches {
+            for _ in 0..needed {
+                let c = self.lookahead.pop_front().unwrap();
+                if self.marked {
+                    self.history.push_back(c);
+                }
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Record the current read position so that a subsequent [AsciiDecoder::rewind] can return
+    /// here. Only the most recent mark is retained. From this point on, delivered characters are
+    /// retained until the mark is rewound or cleared.
+    ///
+    /// The retained region is bounded by the fixed inline buffer: at most [MAX_LOOKAHEAD]
+    /// characters may be read between a `mark` and its [AsciiDecoder::rewind]. Reading beyond that
+    /// cap drops the overflowing characters, so a subsequent `rewind` cannot faithfully replay the
+    /// region — callers must keep marked regions within [MAX_LOOKAHEAD] characters.
+    pub fn mark(&mut self) {
+        self.marked = true;
+        self.history.clear();
+    }
+
+    /// Return the decoder to the last [AsciiDecoder::mark]. Characters read since the mark are
+    /// re-delivered from the retained buffer, so no re-read of the underlying source is required.
+    /// The mark remains active, allowing the same region to be replayed again. Replay is faithful
+    /// only when no more than [MAX_LOOKAHEAD] characters were read since the mark; see
+    /// [AsciiDecoder::mark].
+    pub fn rewind(&mut self) {
+        for i in (0..self.history.len()).rev() {
+            if let Some(c) = self.history.get(i) {
+                self.lookahead.push_front(c);
+            }
+        }
+        self.history.clear();
+    }
+
+    /// Clear the active mark, releasing any retained characters. Has no effect if no mark is set.
+    pub fn clear_mark(&mut self) {
+        self.marked = false;
+        self.history.clear();
+    }
+
+    /// Consume this decoder and return a *fallible* iterator over the stream. Unlike the lenient
+    /// [Iterator] impl (which turns every error into a premature `None`), the returned adaptor
+    /// yields `Ok(c)` for each decoded character, stops cleanly at end-of-input, and propagates a
+    /// genuine [DecoderErrorCode::OutOfRange] or [DecoderErrorCode::StreamFailure] as an `Err`
+    /// item stamped with the byte [index](DecoderError::index) (and, for `OutOfRange`, the
+    /// offending [byte](DecoderError::byte)).
+    pub fn results(self) -> AsciiDecoderResults<'a, Buffer> {
+        AsciiDecoderResults {
+            decoder: self,
+            done: false,
+        }
+    }
 }
 
-impl<'a, B: BufRead> Iterator for AsciiDecoder<'a, B> {
+/// A fallible iterator over an [AsciiDecoder], as produced by [AsciiDecoder::results].
+pub struct AsciiDecoderResults<'a, B: ByteSource> {
+    decoder: AsciiDecoder<'a, B>,
+    /// Set once a terminal error (or end-of-input) has been yielded, after which the iterator is
+    /// fused to `None`
+    done: bool,
+}
+
+impl<'a, B: ByteSource> Iterator for AsciiDecoderResults<'a, B> {
+    type Item = DecoderResult<char>;
+    /// Decode the next character, surfacing real decode errors rather than swallowing them
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.decoder.decode_next() {
+            Ok(c) => Some(Ok(c)),
+            Err(e) => {
+                self.done = true;
+                match e.code {
+                    DecoderErrorCode::EndOfInput => None,
+                    DecoderErrorCode::OutOfRange => {
+                        // the offending byte is still at the front of the source buffer; read it
+                        // for the diagnostic and consume it so a terminal error is yielded exactly
+                        // once rather than re-read on every poll
+                        let byte = self
+                            .decoder
+                            .input
+                            .source_fill_buf()
+                            .ok()
+                            .and_then(|buf| buf.first().copied());
+                        let e = e.at(self.decoder.index);
+                        self.decoder.input.source_consume(1);
+                        self.decoder.index += 1;
+                        Some(Err(match byte {
+                            Some(b) => e.with_byte(b),
+                            None => e,
+                        }))
+                    }
+                    _ => Some(Err(e.at(self.decoder.index))),
+                }
+            }
+        }
+    }
+}
+
+impl<'a, B: ByteSource> Iterator for AsciiDecoder<'a, B> {
     type Item = char;
     /// Decode the next character from the underlying stream
     fn next(&mut self) -> Option<Self::Item> {
-        match self.decode_next() {
-            Ok(c) => Some(c),
-            Err(_) => None,
-        }
+        self.decode_next().ok()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use std::fs::File;
     use std::io::BufReader;
@@ -152,4 +307,57 @@ mod tests {
         assert_eq!(decoder.count(), 6406307);
         println!("Counted fuzz file in {:?}", start.elapsed());
     }
+
+    #[test]
+    fn peek_does_not_advance() {
+        let buffer: &[u8] = b"abc";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = AsciiDecoder::new(&mut reader);
+        assert_eq!(decoder.peek(), Some('a'));
+        assert_eq!(decoder.peek(), Some('a'));
+        assert_eq!(decoder.decode_next().unwrap(), 'a');
+        assert_eq!(decoder.peek(), Some('b'));
+    }
+
+    #[test]
+    fn try_read_string_consumes_on_match() {
+        let buffer: &[u8] = b"true,";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = AsciiDecoder::new(&mut reader);
+        assert!(decoder.try_read_string("true"));
+        assert_eq!(decoder.decode_next().unwrap(), ',');
+    }
+
+    #[test]
+    fn try_read_string_leaves_stream_untouched_on_mismatch() {
+        let buffer: &[u8] = b"true";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = AsciiDecoder::new(&mut reader);
+        assert!(!decoder.try_read_string("fals"));
+        let rest: String = decoder.collect();
+        assert_eq!(rest, "true");
+    }
+
+    #[test]
+    fn try_read_string_spans_a_refill_boundary() {
+        let buffer: &[u8] = b"null";
+        let mut reader = BufReader::with_capacity(1, buffer);
+        let mut decoder = AsciiDecoder::new(&mut reader);
+        assert!(decoder.try_read_string("null"));
+        assert_eq!(decoder.peek(), None);
+    }
+
+    #[test]
+    fn mark_and_rewind_replays_the_region() {
+        let buffer: &[u8] = b"abcdef";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = AsciiDecoder::new(&mut reader);
+        assert_eq!(decoder.decode_next().unwrap(), 'a');
+        decoder.mark();
+        assert_eq!(decoder.decode_next().unwrap(), 'b');
+        assert_eq!(decoder.decode_next().unwrap(), 'c');
+        decoder.rewind();
+        let rest: String = decoder.collect();
+        assert_eq!(rest, "bcdef");
+    }
 }
@@ -1,17 +1,179 @@
 //! Common types used throughout the rest of the crate
-use std::borrow::Cow;
-use std::fmt::{Debug, Display, Formatter};
+use core::fmt::{Debug, Display, Formatter};
 
 /// General result type used by a decoder instance
 pub type DecoderResult<T> = Result<T, DecoderError>;
 
+/// The type used to carry an error message. When the `std` feature is enabled this is a
+/// [`Cow`](std::borrow::Cow) so that callers may attach owned strings; in a `no_std` build it
+/// collapses to a plain `&'static str`, which is all the crate itself ever needs.
+#[cfg(feature = "std")]
+pub type DecoderErrorMessage = std::borrow::Cow<'static, str>;
+
+/// See [DecoderErrorMessage] for the `std` counterpart.
+#[cfg(not(feature = "std"))]
+pub type DecoderErrorMessage = &'static str;
+
+/// A source of bytes for a decoder. This is a minimal [`fill_buf`](ByteSource::source_fill_buf)/
+/// [`consume`](ByteSource::source_consume) interface modelled on [std::io::BufRead], so that the
+/// decoders can run against an in-memory slice on `no_std` targets as well as against any
+/// buffered reader when the `std` feature is enabled.
+pub trait ByteSource {
+    /// Borrow the bytes currently available without consuming them. An empty slice signals that
+    /// the source has been exhausted.
+    fn source_fill_buf(&mut self) -> DecoderResult<&[u8]>;
+
+    /// Mark `amount` bytes from the front of the last [ByteSource::source_fill_buf] slice as
+    /// consumed.
+    fn source_consume(&mut self, amount: usize);
+}
+
+/// A byte slice is a trivially correct [ByteSource]: the outstanding slice *is* the buffer, and
+/// consuming simply advances it. Provided only on `no_std`, because under `std` every
+/// [std::io::BufRead] (slices included) already gets an impl via the blanket below.
+#[cfg(not(feature = "std"))]
+impl ByteSource for &[u8] {
+    fn source_fill_buf(&mut self) -> DecoderResult<&[u8]> {
+        Ok(self)
+    }
+
+    fn source_consume(&mut self, amount: usize) {
+        *self = &self[amount..];
+    }
+}
+
+/// Any [std::io::BufRead] is a [ByteSource], which keeps the file- and reader-based constructors
+/// working unchanged under the default `std` build.
+#[cfg(feature = "std")]
+impl<T: std::io::BufRead> ByteSource for T {
+    fn source_fill_buf(&mut self) -> DecoderResult<&[u8]> {
+        match std::io::BufRead::fill_buf(self) {
+            Ok(buf) => Ok(buf),
+            Err(_) => Err(DecoderError {
+                code: DecoderErrorCode::StreamFailure,
+                message: "failed to read input".into(),
+                index: 0,
+                byte: None,
+            }),
+        }
+    }
+
+    fn source_consume(&mut self, amount: usize) {
+        std::io::BufRead::consume(self, amount)
+    }
+}
+
+/// Maximum number of characters that may be held in a decoder's lookahead/rewind window. This
+/// fixes the capacity of the inline [InlineChars] buffer so the decoders need no heap allocation,
+/// in the spirit of the `ArrayVec`/`ArrayString` types used by other `no_std` decoders.
+pub const LOOKAHEAD_CAPACITY: usize = 64;
+
+/// A fixed-capacity, inline (stack-allocated) double-ended buffer of [char]s. It backs the
+/// lookahead and rewind machinery without pulling in `alloc`, trading an unbounded window for a
+/// bounded one of [LOOKAHEAD_CAPACITY] characters.
+#[derive(Debug)]
+pub struct InlineChars {
+    buf: [char; LOOKAHEAD_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl InlineChars {
+    /// Create an empty buffer
+    pub fn new() -> Self {
+        InlineChars {
+            buf: ['\0'; LOOKAHEAD_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// The number of characters currently buffered
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the buffer is at capacity
+    pub fn is_full(&self) -> bool {
+        self.len == LOOKAHEAD_CAPACITY
+    }
+
+    /// The `i`-th character from the front, if present
+    pub fn get(&self, i: usize) -> Option<char> {
+        if i < self.len {
+            Some(self.buf[(self.head + i) % LOOKAHEAD_CAPACITY])
+        } else {
+            None
+        }
+    }
+
+    /// The character at the front, if any
+    pub fn front(&self) -> Option<char> {
+        self.get(0)
+    }
+
+    /// Push a character onto the back. Returns `false` (and leaves the buffer unchanged) when the
+    /// buffer is already full.
+    pub fn push_back(&mut self, c: char) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let tail = (self.head + self.len) % LOOKAHEAD_CAPACITY;
+        self.buf[tail] = c;
+        self.len += 1;
+        true
+    }
+
+    /// Push a character onto the front. Returns `false` (and leaves the buffer unchanged) when the
+    /// buffer is already full.
+    pub fn push_front(&mut self, c: char) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.head = (self.head + LOOKAHEAD_CAPACITY - 1) % LOOKAHEAD_CAPACITY;
+        self.buf[self.head] = c;
+        self.len += 1;
+        true
+    }
+
+    /// Pop a character from the front, if any
+    pub fn pop_front(&mut self) -> Option<char> {
+        if self.len == 0 {
+            return None;
+        }
+        let c = self.buf[self.head];
+        self.head = (self.head + 1) % LOOKAHEAD_CAPACITY;
+        self.len -= 1;
+        Some(c)
+    }
+
+    /// Empty the buffer
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+impl Default for InlineChars {
+    fn default() -> Self {
+        InlineChars::new()
+    }
+}
+
 /// Enumeration of different decoder errors
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DecoderErrorCode {
     /// Something went pear-shaped in the underlying stream
     StreamFailure,
     /// Detected an invalid byte sequence
     InvalidByteSequence,
+    /// A byte fell outside the range valid for the selected encoding
+    OutOfRange,
     /// The end of the input has been reached
     EndOfInput,
 }
@@ -23,33 +185,71 @@ pub struct DecoderError {
     pub code: DecoderErrorCode,
 
     /// Associated error message
-    pub message: Cow<'static, str>,
+    pub message: DecoderErrorMessage,
+
+    /// The byte index into the input at which the error was detected. Defaults to `0` and is
+    /// populated by a decoder (e.g. via [DecoderError::at]) once the offending position is known.
+    pub index: usize,
+
+    /// The offending byte value, where one can be identified. `None` for errors (such as
+    /// end-of-input) that are not attributable to a specific byte.
+    pub byte: Option<u8>,
+}
+
+impl DecoderError {
+    /// Stamp the byte index at which this error occurred, returning the updated error.
+    pub fn at(mut self, index: usize) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Record the offending byte value, returning the updated error.
+    pub fn with_byte(mut self, byte: u8) -> Self {
+        self.byte = Some(byte);
+        self
+    }
 }
 
 impl Display for DecoderError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Code: {:?}, Message: {}", self.code, self.message)
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.byte {
+            Some(b) => write!(
+                f,
+                "Code: {:?}, Message: {}, Index: {}, Byte: {:#04x}",
+                self.code, self.message, self.index, b
+            ),
+            None => write!(
+                f,
+                "Code: {:?}, Message: {}, Index: {}",
+                self.code, self.message, self.index
+            ),
+        }
     }
 }
 
-/// Helper macro for generating errors
+/// Helper macro for generating errors. Expands to a bare [DecoderError] value; wrap it in `Err(..)`
+/// at the call site.
 #[macro_export]
 macro_rules! decoder_error {
     ($code : expr, $msg : expr) => {
-        Err(DecoderError {
+        DecoderError {
             code: $code,
-            message: $msg.into()
-        })
+            message: $msg.into(),
+            index: 0,
+            byte: None
+        }
     }
 }
 
 #[macro_export]
 macro_rules! end_of_input {
     () => {
-        Err(DecoderError {
+        DecoderError {
             code: DecoderErrorCode::EndOfInput,
-            message: "end of input reached".into()
-        })
+            message: "end of input reached".into(),
+            index: 0,
+            byte: None
+        }
     }
 }
 
@@ -57,9 +257,11 @@ macro_rules! end_of_input {
 #[macro_export]
 macro_rules! invalid_byte_sequence {
     () => {
-        Err(DecoderError {
+        DecoderError {
             code: DecoderErrorCode::InvalidByteSequence,
-            message: "invalid byte sequence".into()
-        })
+            message: "invalid byte sequence".into(),
+            index: 0,
+            byte: None
+        }
     }
 }
@@ -70,6 +70,148 @@
 //!     }
 //! ```
 //!
+//! ### `no_std` / embedded targets
+//!
+//! The decoders do not require `std`. The crate is `std` by default, but with default features
+//! disabled it builds for `no_std` targets: the `BufRead` bound is swapped for the in-crate
+//! [`ByteSource`](common::ByteSource) trait (implemented for `&[u8]` in a `no_std` build and for
+//! any [std::io::BufRead] under `std`), the growable staging buffer is replaced by a fixed
+//! four-byte array, and lookahead and rewind use the fixed-capacity inline
+//! [`InlineChars`](common::InlineChars) window. The public decoder and
+//! iterator API is identical under both configurations, so downstream code compiles unchanged:
+//!
+//! ```rust
+//!     # use chisel_decoders::utf8::Utf8Decoder;
+//!
+//!     let mut source: &[u8] = &[0x68, 0x69];
+//!     let decoder = Utf8Decoder::new(&mut source);
+//!     let decoded: std::string::String = decoder.collect();
+//!     assert_eq!(decoded, "hi");
+//! ```
+//!
+#![cfg_attr(not(feature = "std"), no_std)]
+
 pub mod ascii;
 pub mod common;
+pub mod single_byte;
+pub mod utf16;
 pub mod utf8;
+
+#[cfg(feature = "std")]
+use std::io::BufRead;
+
+#[cfg(feature = "std")]
+use crate::ascii::AsciiDecoder;
+#[cfg(feature = "std")]
+use crate::single_byte::SingleByteDecoder;
+#[cfg(feature = "std")]
+use crate::utf16::{Endianness, Utf16Decoder};
+#[cfg(feature = "std")]
+use crate::utf8::Utf8Decoder;
+
+/// The text encodings the transcoding front end can decode, all normalised to Rust [char].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    /// 7-bit ASCII
+    Ascii,
+    /// UTF-8
+    Utf8,
+    /// UTF-16, little-endian
+    Utf16Le,
+    /// UTF-16, big-endian
+    Utf16Be,
+    /// ISO-8859-1 (Latin-1)
+    Latin1,
+    /// Windows-1252
+    Windows1252,
+}
+
+impl Encoding {
+    /// Wrap `reader` in the decoder for this encoding, yielding a boxed [char] iterator.
+    #[cfg(feature = "std")]
+    fn decoder<'a, B: BufRead>(self, reader: &'a mut B) -> Box<dyn Iterator<Item = char> + 'a> {
+        match self {
+            Encoding::Ascii => Box::new(AsciiDecoder::new(reader)),
+            Encoding::Utf8 => Box::new(Utf8Decoder::new(reader)),
+            Encoding::Utf16Le => Box::new(Utf16Decoder::new(reader, Endianness::Little)),
+            Encoding::Utf16Be => Box::new(Utf16Decoder::new(reader, Endianness::Big)),
+            Encoding::Latin1 => Box::new(SingleByteDecoder::latin1(reader)),
+            Encoding::Windows1252 => Box::new(SingleByteDecoder::windows1252(reader)),
+        }
+    }
+}
+
+/// Construct a decoder for an arbitrary text stream by sniffing a leading byte-order mark, with a
+/// caller-specified fallback. A UTF-8 BOM (`EF BB BF`), a UTF-16LE BOM (`FF FE`) or a UTF-16BE BOM
+/// (`FE FF`) selects (and is consumed from) the matching encoding; in the absence of a recognised
+/// BOM the stream is decoded using `default`. The returned iterator yields the decoded [char]s
+/// regardless of the encoding that was detected.
+#[cfg(feature = "std")]
+pub fn decode_with_default<'a, B: BufRead>(
+    reader: &'a mut B,
+    default: Encoding,
+) -> Box<dyn Iterator<Item = char> + 'a> {
+    let prefix = reader.fill_buf().unwrap_or(&[]);
+    let encoding = if prefix.starts_with(&[0xef, 0xbb, 0xbf]) {
+        reader.consume(3);
+        Encoding::Utf8
+    } else if prefix.starts_with(&[0xff, 0xfe]) {
+        reader.consume(2);
+        Encoding::Utf16Le
+    } else if prefix.starts_with(&[0xfe, 0xff]) {
+        reader.consume(2);
+        Encoding::Utf16Be
+    } else {
+        default
+    };
+    encoding.decoder(reader)
+}
+
+/// Construct a decoder for an arbitrary text stream by sniffing a leading byte-order mark,
+/// defaulting to UTF-8 when no recognised BOM is present. A convenience wrapper around
+/// [decode_with_default].
+#[cfg(feature = "std")]
+pub fn from_reader<'a, B: BufRead>(reader: &'a mut B) -> Box<dyn Iterator<Item = char> + 'a> {
+    decode_with_default(reader, Encoding::Utf8)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::io::BufReader;
+
+    use crate::{decode_with_default, from_reader, Encoding};
+
+    fn sniff(buffer: &[u8]) -> String {
+        let mut reader = BufReader::new(buffer);
+        from_reader(&mut reader).collect()
+    }
+
+    #[test]
+    fn utf8_bom_is_detected_and_consumed() {
+        assert_eq!(sniff(&[0xef, 0xbb, 0xbf, 0x68, 0x69]), "hi");
+    }
+
+    #[test]
+    fn utf16le_bom_is_detected_and_consumed() {
+        assert_eq!(sniff(&[0xff, 0xfe, 0x41, 0x00, 0x42, 0x00]), "AB");
+    }
+
+    #[test]
+    fn utf16be_bom_is_detected_and_consumed() {
+        assert_eq!(sniff(&[0xfe, 0xff, 0x00, 0x41, 0x00, 0x42]), "AB");
+    }
+
+    #[test]
+    fn no_bom_falls_back_to_utf8() {
+        assert_eq!(sniff(&[0x68, 0x69]), "hi");
+    }
+
+    #[test]
+    fn no_bom_honours_the_supplied_default() {
+        // 0xe9 is 'é' under Latin-1; absent a BOM the caller's default encoding is used
+        let buffer: &[u8] = &[0x68, 0xe9];
+        let mut reader = BufReader::new(buffer);
+        let decoded: String = decode_with_default(&mut reader, Encoding::Latin1).collect();
+        assert_eq!(decoded, "hé");
+    }
+}
@@ -0,0 +1,146 @@
+#![allow(dead_code)]
+//! Character-oriented decoders for the single-byte encodings ISO-8859-1 (Latin-1) and
+//! Windows-1252. Each input byte maps to exactly one [char]: the lower half (`0x00..=0x7f`) is
+//! ASCII, and the upper half (`0x80..=0xff`) is resolved through a 128-entry lookup table.
+use crate::common::*;
+use crate::decoder_error;
+
+/// ISO-8859-1 (Latin-1) upper half: each byte maps directly to the code point of the same value.
+const LATIN1_HIGH: [char; 128] = {
+    let mut table = ['\0'; 128];
+    let mut i = 0;
+    while i < 128 {
+        table[i] = unsafe { char::from_u32_unchecked(0x80 + i as u32) };
+        i += 1;
+    }
+    table
+};
+
+/// Windows-1252 upper half. Differs from Latin-1 only in `0x80..=0x9f`, where most positions
+/// carry printable characters; the five positions left undefined by the standard map to the
+/// corresponding C1 control code point.
+const WINDOWS_1252_HIGH: [char; 128] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{0192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02c6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{8d}', '\u{017d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02dc}', '\u{2122}', '\u{0161}', '\u{203a}', '\u{0153}', '\u{9d}', '\u{017e}', '\u{0178}',
+    '\u{a0}', '\u{a1}', '\u{a2}', '\u{a3}', '\u{a4}', '\u{a5}', '\u{a6}', '\u{a7}',
+    '\u{a8}', '\u{a9}', '\u{aa}', '\u{ab}', '\u{ac}', '\u{ad}', '\u{ae}', '\u{af}',
+    '\u{b0}', '\u{b1}', '\u{b2}', '\u{b3}', '\u{b4}', '\u{b5}', '\u{b6}', '\u{b7}',
+    '\u{b8}', '\u{b9}', '\u{ba}', '\u{bb}', '\u{bc}', '\u{bd}', '\u{be}', '\u{bf}',
+    '\u{c0}', '\u{c1}', '\u{c2}', '\u{c3}', '\u{c4}', '\u{c5}', '\u{c6}', '\u{c7}',
+    '\u{c8}', '\u{c9}', '\u{ca}', '\u{cb}', '\u{cc}', '\u{cd}', '\u{ce}', '\u{cf}',
+    '\u{d0}', '\u{d1}', '\u{d2}', '\u{d3}', '\u{d4}', '\u{d5}', '\u{d6}', '\u{d7}',
+    '\u{d8}', '\u{d9}', '\u{da}', '\u{db}', '\u{dc}', '\u{dd}', '\u{de}', '\u{df}',
+    '\u{e0}', '\u{e1}', '\u{e2}', '\u{e3}', '\u{e4}', '\u{e5}', '\u{e6}', '\u{e7}',
+    '\u{e8}', '\u{e9}', '\u{ea}', '\u{eb}', '\u{ec}', '\u{ed}', '\u{ee}', '\u{ef}',
+    '\u{f0}', '\u{f1}', '\u{f2}', '\u{f3}', '\u{f4}', '\u{f5}', '\u{f6}', '\u{f7}',
+    '\u{f8}', '\u{f9}', '\u{fa}', '\u{fb}', '\u{fc}', '\u{fd}', '\u{fe}', '\u{ff}',
+];
+
+/// A decoder for a single-byte encoding, which takes a ref to a [ByteSource] instance and a
+/// lookup table for the upper half of the byte range.
+pub struct SingleByteDecoder<'a, B: ByteSource> {
+    /// The input stream
+    input: &'a mut B,
+    /// Lookup table for bytes in the range `0x80..=0xff`
+    high: &'static [char; 128],
+    /// The current byte index into the input
+    index: usize,
+}
+
+impl<'a, Buffer: ByteSource> SingleByteDecoder<'a, Buffer> {
+    /// Create a new ISO-8859-1 (Latin-1) decoder
+    pub fn latin1(r: &'a mut Buffer) -> Self {
+        SingleByteDecoder {
+            input: r,
+            high: &LATIN1_HIGH,
+            index: 0,
+        }
+    }
+
+    /// Create a new Windows-1252 decoder
+    pub fn windows1252(r: &'a mut Buffer) -> Self {
+        SingleByteDecoder {
+            input: r,
+            high: &WINDOWS_1252_HIGH,
+            index: 0,
+        }
+    }
+
+    /// Attempt to decode the next character in the underlying stream. Exactly one byte is
+    /// consumed per decode; a single-byte encoding can never produce an error other than
+    /// end-of-input or a stream failure.
+    pub fn decode_next(&mut self) -> DecoderResult<char> {
+        let available = self.input.source_fill_buf()?;
+        let byte = match available.first() {
+            Some(b) => *b,
+            None => {
+                return Err(decoder_error!(
+                    DecoderErrorCode::EndOfInput,
+                    "end of input reached"
+                ))
+            }
+        };
+        self.input.source_consume(1);
+        self.index += 1;
+        if byte < 0x80 {
+            Ok(byte as char)
+        } else {
+            Ok(self.high[(byte - 0x80) as usize])
+        }
+    }
+}
+
+impl<'a, B: ByteSource> Iterator for SingleByteDecoder<'a, B> {
+    type Item = char;
+    /// Decode the next character from the underlying stream
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decode_next().ok()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::io::BufReader;
+
+    use crate::single_byte::SingleByteDecoder;
+
+    fn latin1(buffer: &[u8]) -> String {
+        let mut reader = BufReader::new(buffer);
+        SingleByteDecoder::latin1(&mut reader).collect()
+    }
+
+    fn windows1252(buffer: &[u8]) -> String {
+        let mut reader = BufReader::new(buffer);
+        SingleByteDecoder::windows1252(&mut reader).collect()
+    }
+
+    #[test]
+    fn latin1_maps_upper_half_to_same_code_point() {
+        // 'é' (0xe9) and '©' (0xa9) map straight through to U+00E9 / U+00A9
+        assert_eq!(latin1(&[0x48, 0x69, 0xe9, 0xa9]), "Hié©");
+    }
+
+    #[test]
+    fn windows1252_spot_checks() {
+        // the positions that differ from Latin-1 in 0x80..=0x9f
+        assert_eq!(windows1252(&[0x80, 0x99, 0x92, 0x9c]), "€™’œ");
+    }
+
+    #[test]
+    fn windows1252_undefined_positions_pass_through() {
+        // 0x81, 0x8d, 0x8f, 0x90 and 0x9d are undefined and map to the matching C1 code point
+        assert_eq!(
+            windows1252(&[0x81, 0x8d, 0x8f, 0x90, 0x9d]),
+            "\u{81}\u{8d}\u{8f}\u{90}\u{9d}"
+        );
+    }
+
+    #[test]
+    fn encodings_differ_in_the_control_band() {
+        // 0x80 is a C1 control in Latin-1 but the Euro sign in Windows-1252
+        assert_eq!(latin1(&[0x80]), "\u{80}");
+        assert_eq!(windows1252(&[0x80]), "€");
+    }
+}
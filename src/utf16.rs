@@ -0,0 +1,226 @@
+#![allow(dead_code)]
+//! A character-oriented decoder implementation that will take an underlying [std::u8] (byte)
+//! source and produce a stream of decoded Unicode characters from a UTF-16 encoded stream. Both
+//! little- and big-endian byte orders are supported via the [Endianness] parameter.
+
+use crate::common::*;
+use crate::{decoder_error, end_of_input, invalid_byte_sequence};
+
+/// The Unicode REPLACEMENT CHARACTER, substituted for ill-formed sequences in lossy mode
+const REPLACEMENT_CHARACTER: char = '\u{fffd}';
+
+/// Low bound of the high (leading) surrogate range
+const HIGH_SURROGATE_LOW_BOUND: u32 = 0xd800;
+/// High bound of the high (leading) surrogate range
+const HIGH_SURROGATE_HIGH_BOUND: u32 = 0xdbff;
+/// Low bound of the low (trailing) surrogate range
+const LOW_SURROGATE_LOW_BOUND: u32 = 0xdc00;
+/// High bound of the low (trailing) surrogate range
+const LOW_SURROGATE_HIGH_BOUND: u32 = 0xdfff;
+/// Base added when reconstructing a scalar value from a surrogate pair
+const SUPPLEMENTARY_BASE: u32 = 0x10000;
+
+/// The byte order of a UTF-16 encoded stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Endianness {
+    /// Least-significant byte first
+    Little,
+    /// Most-significant byte first
+    Big,
+}
+
+/// A UTF-16 decoder, which takes a ref to a [ByteSource] instance and a byte [Endianness]. Bytes
+/// are pulled from the underlying source on demand via [ByteSource::source_fill_buf]/
+/// [ByteSource::source_consume], so the decoder runs in constant memory regardless of input size.
+pub struct Utf16Decoder<'a, B: ByteSource> {
+    /// The input stream
+    input: &'a mut B,
+    /// The byte order used to assemble code units
+    endianness: Endianness,
+    /// The current byte index into the input
+    index: usize,
+    /// When set, ill-formed sequences are replaced with [REPLACEMENT_CHARACTER] rather than
+    /// surfacing an error
+    lossy: bool,
+    /// A code unit read while looking for the low half of a surrogate pair but found not to be a
+    /// low surrogate. It is retained here so the following [Utf16Decoder::decode_next] reprocesses
+    /// it rather than discarding a perfectly valid character.
+    pending: Option<u16>,
+}
+
+impl<'a, Buffer: ByteSource> Utf16Decoder<'a, Buffer> {
+    /// Create a new decoder for the given byte order
+    pub fn new(r: &'a mut Buffer, endianness: Endianness) -> Self {
+        Utf16Decoder {
+            input: r,
+            endianness,
+            index: 0,
+            lossy: false,
+            pending: None,
+        }
+    }
+
+    /// Create a new decoder that substitutes [REPLACEMENT_CHARACTER] for unpaired surrogates
+    /// rather than surfacing an error
+    pub fn new_lossy(r: &'a mut Buffer, endianness: Endianness) -> Self {
+        Utf16Decoder {
+            input: r,
+            endianness,
+            index: 0,
+            lossy: true,
+            pending: None,
+        }
+    }
+
+    /// Pull the next byte from the underlying source, refilling its buffer as required. Returns
+    /// `Ok(None)` once the input has been exhausted.
+    fn next_byte(&mut self) -> DecoderResult<Option<u8>> {
+        let available = self.input.source_fill_buf()?;
+        if available.is_empty() {
+            return Ok(None);
+        }
+        let b = available[0];
+        self.input.source_consume(1);
+        self.index += 1;
+        Ok(Some(b))
+    }
+
+    /// Read a single 16-bit code unit, honouring the configured [Endianness]. Returns `Ok(None)`
+    /// at a clean end-of-input and an [DecoderErrorCode::InvalidByteSequence] when a trailing odd
+    /// byte is encountered.
+    fn next_code_unit(&mut self) -> DecoderResult<Option<u16>> {
+        if let Some(unit) = self.pending.take() {
+            return Ok(Some(unit));
+        }
+        let first = match self.next_byte()? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let second = match self.next_byte()? {
+            Some(b) => b,
+            None => return Err(invalid_byte_sequence!()),
+        };
+        let unit = match self.endianness {
+            Endianness::Little => (second as u16) << 8 | first as u16,
+            Endianness::Big => (first as u16) << 8 | second as u16,
+        };
+        Ok(Some(unit))
+    }
+
+    /// Attempt to decode the next character in the underlying stream, combining surrogate pairs
+    /// where present.
+    pub fn decode_next(&mut self) -> DecoderResult<char> {
+        let high = match self.next_code_unit()? {
+            Some(u) => u as u32,
+            None => return Err(end_of_input!()),
+        };
+
+        // a lone low surrogate is always unpaired
+        if (LOW_SURROGATE_LOW_BOUND..=LOW_SURROGATE_HIGH_BOUND).contains(&high) {
+            return self.unpaired_surrogate();
+        }
+
+        if (HIGH_SURROGATE_LOW_BOUND..=HIGH_SURROGATE_HIGH_BOUND).contains(&high) {
+            let low = match self.next_code_unit()? {
+                Some(u) => u as u32,
+                None => return self.unpaired_surrogate(),
+            };
+            if (LOW_SURROGATE_LOW_BOUND..=LOW_SURROGATE_HIGH_BOUND).contains(&low) {
+                let value = SUPPLEMENTARY_BASE
+                    + ((high - HIGH_SURROGATE_LOW_BOUND) << 10)
+                    + (low - LOW_SURROGATE_LOW_BOUND);
+                return unsafe { Ok(char::from_u32_unchecked(value)) };
+            }
+            // the second unit is not a low surrogate: the high surrogate is unpaired, but the unit
+            // we read is a valid character in its own right, so retain it for the next decode
+            self.pending = Some(low as u16);
+            return self.unpaired_surrogate();
+        }
+
+        unsafe { Ok(char::from_u32_unchecked(high)) }
+    }
+
+    /// Produce the result for an unpaired surrogate: a [REPLACEMENT_CHARACTER] in lossy mode,
+    /// otherwise an [DecoderErrorCode::InvalidByteSequence].
+    fn unpaired_surrogate(&self) -> DecoderResult<char> {
+        if self.lossy {
+            Ok(REPLACEMENT_CHARACTER)
+        } else {
+            Err(decoder_error!(
+                DecoderErrorCode::InvalidByteSequence,
+                "unpaired surrogate in UTF-16 stream"
+            ))
+        }
+    }
+}
+
+impl<'a, B: ByteSource> Iterator for Utf16Decoder<'a, B> {
+    type Item = char;
+    /// Decode the next character from the underlying stream
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decode_next().ok()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::io::BufReader;
+
+    use crate::common::DecoderErrorCode;
+    use crate::utf16::{Endianness, Utf16Decoder};
+
+    fn decode(buffer: &[u8], endianness: Endianness) -> String {
+        let mut reader = BufReader::new(buffer);
+        Utf16Decoder::new(&mut reader, endianness).collect()
+    }
+
+    fn decode_lossy(buffer: &[u8], endianness: Endianness) -> String {
+        let mut reader = BufReader::new(buffer);
+        Utf16Decoder::new_lossy(&mut reader, endianness).collect()
+    }
+
+    #[test]
+    fn decodes_bmp_little_endian() {
+        assert_eq!(decode(&[0x41, 0x00, 0x42, 0x00], Endianness::Little), "AB");
+    }
+
+    #[test]
+    fn decodes_bmp_big_endian() {
+        assert_eq!(decode(&[0x00, 0x41, 0x00, 0x42], Endianness::Big), "AB");
+    }
+
+    #[test]
+    fn combines_surrogate_pair() {
+        // U+1F600 encodes as the surrogate pair D83D DE00
+        assert_eq!(decode(&[0x3d, 0xd8, 0x00, 0xde], Endianness::Little), "😀");
+        assert_eq!(decode(&[0xd8, 0x3d, 0xde, 0x00], Endianness::Big), "😀");
+    }
+
+    #[test]
+    fn unpaired_surrogate_is_an_error() {
+        let buffer: &[u8] = &[0x3d, 0xd8, 0x41, 0x00];
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = Utf16Decoder::new(&mut reader, Endianness::Little);
+        let err = decoder.decode_next().unwrap_err();
+        assert_eq!(err.code, DecoderErrorCode::InvalidByteSequence);
+    }
+
+    #[test]
+    fn lossy_unpaired_surrogate_retains_following_char() {
+        // a high surrogate followed by a plain BMP char: the surrogate becomes FFFD but the 'A'
+        // must still be decoded rather than dropped
+        assert_eq!(
+            decode_lossy(&[0x3d, 0xd8, 0x41, 0x00], Endianness::Little),
+            "\u{fffd}A"
+        );
+    }
+
+    #[test]
+    fn odd_trailing_byte_is_an_error() {
+        let buffer: &[u8] = &[0x41];
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = Utf16Decoder::new(&mut reader, Endianness::Little);
+        let err = decoder.decode_next().unwrap_err();
+        assert_eq!(err.code, DecoderErrorCode::InvalidByteSequence);
+    }
+}
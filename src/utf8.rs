@@ -1,13 +1,10 @@
 #![allow(dead_code)]
-#![allow(clippy::transmute_int_to_char)]
 //! A character-oriented decoder implementation that will take an underlying [std::u8] (byte) source
 //! and produce a stream of decoded Unicode (UTF-8) characters
-use std::io::BufRead;
-use std::mem::transmute;
 
 use crate::common::*;
 use crate::utf8::SequenceType::Unrecognised;
-use crate::{decoder_error, invalid_byte_sequence};
+use crate::{decoder_error, end_of_input, invalid_byte_sequence};
 
 enum SequenceType {
     Single,
@@ -37,6 +34,30 @@ const TRIPLE_EXCLUDED_HIGH_BOUND: u32 = 0xdfff;
 /// High bound for checking quads
 const QUAD_HIGH_BOUND: u32 = 0x10ffff;
 
+/// The Unicode REPLACEMENT CHARACTER, substituted for ill-formed sequences in lossy mode
+const REPLACEMENT_CHARACTER: char = '\u{fffd}';
+
+/// Minimum value that may be legitimately encoded as a double byte sequence (anything lower is
+/// an overlong encoding)
+const PAIR_LOW_BOUND: u32 = 0x80;
+
+/// Minimum value that may be legitimately encoded as a triple byte sequence
+const TRIPLE_LOW_BOUND: u32 = 0x800;
+
+/// Minimum value that may be legitimately encoded as a quad byte sequence
+const QUAD_LOW_BOUND: u32 = 0x10000;
+
+/// The maximum number of characters a caller may match in a single [Utf8Decoder::try_read_string]
+/// call, and the most that [Utf8Decoder::peek] / `try_read_string` will ever buffer ahead. Fixed
+/// by the inline [LOOKAHEAD_CAPACITY] buffer so that no heap allocation is required.
+pub const MAX_LOOKAHEAD: usize = LOOKAHEAD_CAPACITY;
+
+/// Whether a given byte is a well-formed UTF-8 continuation byte (`0b10xxxxxx`)
+#[inline]
+fn is_continuation(b: u8) -> bool {
+    b >> 6 == 0b10
+}
+
 /// Convenience macro for some bit twiddlin'
 macro_rules! single_byte_sequence {
     ($byte : expr) => {
@@ -106,108 +127,369 @@ fn sequence_type(b: u8) -> SequenceType {
     Unrecognised
 }
 
-/// A UTF-8 decoder, which takes a ref to a [BufRead] instance.
-pub struct Utf8Decoder<'a, B: BufRead> {
+/// A UTF-8 decoder, which takes a ref to a [ByteSource] instance. Bytes are pulled from the
+/// underlying source on demand via [ByteSource::source_fill_buf]/[ByteSource::source_consume], so
+/// the decoder runs in constant memory regardless of the size of the input.
+pub struct Utf8Decoder<'a, B: ByteSource> {
     /// The input stream
     input: &'a mut B,
-    /// Staging buffer
-    buffer: Vec<u8>,
-    init: bool,
+    /// Staging buffer used to reassemble a single multi-byte sequence. At most the leading byte
+    /// plus 3 continuation bytes are ever held here, so a sequence that straddles a buffer-refill
+    /// boundary is carried over correctly.
+    staging: [u8; 4],
+    /// The current byte index into the input
     index: usize,
+    /// When set, ill-formed sequences are replaced with [REPLACEMENT_CHARACTER] rather than
+    /// surfacing an error
+    lossy: bool,
+    /// Characters decoded ahead of the read position by [Utf8Decoder::peek] /
+    /// [Utf8Decoder::try_read_string] and not yet handed back to the consumer
+    lookahead: InlineChars,
+    /// Whether a mark is currently active
+    marked: bool,
+    /// Characters delivered since the active mark, retained so that [Utf8Decoder::rewind] can
+    /// re-deliver them without re-reading the underlying source
+    history: InlineChars,
 }
 
-impl<'a, Buffer: BufRead> Utf8Decoder<'a, Buffer> {
+impl<'a, Buffer: ByteSource> Utf8Decoder<'a, Buffer> {
     /// Create a new decoder with a default buffer size
     pub fn new(r: &'a mut Buffer) -> Self {
         Utf8Decoder {
             input: r,
-            buffer: vec![],
-            init: false,
+            staging: [0; 4],
             index: 0,
+            lossy: false,
+            lookahead: InlineChars::new(),
+            marked: false,
+            history: InlineChars::new(),
         }
     }
 
-    fn init(&mut self) -> DecoderResult<()> {
-        match self.input.read_to_end(&mut self.buffer) {
-            Ok(_) => {
-                self.init = true;
-                Ok(())
-            }
-            Err(_) => Err(decoder_error!(
-                DecoderErrorCode::StreamFailure,
-                "failed to read input"
-            )),
+    /// Create a new decoder that performs *lossy* decoding: instead of failing on an ill-formed
+    /// byte sequence, it follows the Unicode "maximal subpart" substitution rule, emitting a
+    /// single [REPLACEMENT_CHARACTER] per offending group and resuming at the next byte.
+    pub fn new_lossy(r: &'a mut Buffer) -> Self {
+        Utf8Decoder {
+            input: r,
+            staging: [0; 4],
+            index: 0,
+            lossy: true,
+            lookahead: InlineChars::new(),
+            marked: false,
+            history: InlineChars::new(),
         }
     }
 
+    /// Pull the next byte from the underlying source, refilling its buffer as required. Returns
+    /// `Ok(None)` once the input has been exhausted.
+    fn next_byte(&mut self) -> DecoderResult<Option<u8>> {
+        let available = self.input.source_fill_buf()?;
+        if available.is_empty() {
+            return Ok(None);
+        }
+        let b = available[0];
+        self.input.source_consume(1);
+        self.index += 1;
+        Ok(Some(b))
+    }
+
+    /// Peek at the next byte without consuming it, refilling the source buffer as required.
+    /// Returns `Ok(None)` once the input has been exhausted.
+    fn peek_byte(&mut self) -> DecoderResult<Option<u8>> {
+        Ok(self.input.source_fill_buf()?.first().copied())
+    }
+
     /// Attempt to decode the next character in the underlying stream. Assumes the maximum
-    /// number of unicode bytes is 4 *not* 6
+    /// number of unicode bytes is 4 *not* 6. Characters buffered by [Utf8Decoder::peek] or
+    /// [Utf8Decoder::try_read_string] are returned first, ahead of any fresh decode.
     pub fn decode_next(&mut self) -> DecoderResult<char> {
-        if !self.init {
-            self.init()?;
+        let c = match self.lookahead.pop_front() {
+            Some(c) => c,
+            None => self.decode_scalar()?,
+        };
+        if self.marked {
+            self.history.push_back(c);
+        }
+        Ok(c)
+    }
+
+    /// Decode a single scalar value directly from the underlying source, bypassing the lookahead
+    /// buffer.
+    fn decode_scalar(&mut self) -> DecoderResult<char> {
+        if self.lossy {
+            self.decode_next_lossy()
+        } else {
+            self.decode_next_strict()
         }
+    }
 
-        if self.index >= self.buffer.len() {
-            return Err(decoder_error!(
-                DecoderErrorCode::EndOfInput,
-                "end of input reached"
-            ));
+    /// Return the next character without advancing the read position, or `None` at end-of-input
+    /// (or, in strict mode, on an ill-formed sequence). Repeated calls return the same character
+    /// until it is consumed via [Utf8Decoder::decode_next].
+    pub fn peek(&mut self) -> Option<char> {
+        if self.lookahead.is_empty() {
+            match self.decode_scalar() {
+                Ok(c) => {
+                    self.lookahead.push_back(c);
+                }
+                Err(_) => return None,
+            }
         }
+        self.lookahead.front()
+    }
 
-        match sequence_type(self.buffer[self.index]) {
-            SequenceType::Single => unsafe {
-                self.index += 1;
-                Ok(transmute(self.buffer[self.index - 1] as u32))
-            },
-            SequenceType::Pair => unsafe {
-                self.index += 2;
-                Ok(transmute(decode_pair!(
-                    &self.buffer[self.index - 2..self.index]
-                )))
-            },
-            SequenceType::Triple => unsafe {
-                self.index += 3;
-                let value = decode_triple!(&self.buffer[self.index - 3..self.index]);
-                if (TRIPLE_EXCLUDED_LOW_BOUND..=TRIPLE_EXCLUDED_HIGH_BOUND).contains(&value) {
-                    Err(decoder_error!(
-                        DecoderErrorCode::InvalidByteSequence,
-                        "value falls within forbidden range [0xd800, 0xdfff]"
-                    ))
-                } else {
-                    Ok(transmute(value))
+    /// Consume and return `true` if, and only if, the upcoming characters match `s` exactly; on a
+    /// mismatch the stream is left untouched. At most [MAX_LOOKAHEAD] characters may be matched in
+    /// a single call — a longer `s` (or one that cannot be fully read) simply returns `false`.
+    pub fn try_read_string(&mut self, s: &str) -> bool {
+        let needed = s.chars().count();
+        if needed == 0 {
+            return true;
+        }
+        if needed > MAX_LOOKAHEAD {
+            return false;
+        }
+        while self.lookahead.len() < needed {
+            match self.decode_scalar() {
+                Ok(c) => {
+                    self.lookahead.push_back(c);
                 }
-            },
-            SequenceType::Quad => unsafe {
-                self.index += 4;
-                let value = decode_quad!(&self.buffer[self.index - 4..self.index]);
-                if value > QUAD_HIGH_BOUND {
-                    Err(decoder_error!(
-                        DecoderErrorCode::InvalidByteSequence,
-                        "value falls outside maximum bound 0x10ffff"
-                    ))
-                } else {
-                    Ok(transmute(value))
+                Err(_) => return false,
+            }
+        }
+        let matches = s
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.lookahead.get(i) == Some(c));
+        if matches {
+            for _ in 0..needed {
+                let c = self.lookahead.pop_front().unwrap();
+                if self.marked {
+                    self.history.push_back(c);
                 }
-            },
-            Unrecognised => {
-                invalid_byte_sequence!()
             }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Record the current read position so that a subsequent [Utf8Decoder::rewind] can return
+    /// here. Only the most recent mark is retained. From this point on, delivered characters are
+    /// retained until the mark is rewound or cleared.
+    ///
+    /// The retained region is bounded by the fixed inline buffer: at most [MAX_LOOKAHEAD]
+    /// characters may be read between a `mark` and its [Utf8Decoder::rewind]. Reading beyond that
+    /// cap drops the overflowing characters, so a subsequent `rewind` cannot faithfully replay the
+    /// region — callers must keep marked regions within [MAX_LOOKAHEAD] characters.
+    pub fn mark(&mut self) {
+        self.marked = true;
+        self.history.clear();
+    }
+
+    /// Return the decoder to the last [Utf8Decoder::mark]. Characters read since the mark are
+    /// re-delivered from the retained buffer, so no re-read of the underlying source is required.
+    /// The mark remains active, allowing the same region to be replayed again. Replay is faithful
+    /// only when no more than [MAX_LOOKAHEAD] characters were read since the mark; see
+    /// [Utf8Decoder::mark].
+    pub fn rewind(&mut self) {
+        for i in (0..self.history.len()).rev() {
+            if let Some(c) = self.history.get(i) {
+                self.lookahead.push_front(c);
+            }
+        }
+        self.history.clear();
+    }
+
+    /// Clear the active mark, releasing any retained characters. Has no effect if no mark is set.
+    pub fn clear_mark(&mut self) {
+        self.marked = false;
+        self.history.clear();
+    }
+
+    /// Strict decode: an unrecognised lead byte, a sequence cut short by end-of-input, a triple
+    /// decoding into the surrogate range or a quad beyond 0x10ffff is surfaced as an error stamped
+    /// with the offset of the offending lead byte and its value, so a caller can report "invalid
+    /// byte 0xC3 at offset 12,345". Trailing bytes are not checked to be continuation bytes, and
+    /// overlong encodings are decoded rather than rejected.
+    fn decode_next_strict(&mut self) -> DecoderResult<char> {
+        // the offset of the lead byte, captured before next_byte advances the index
+        let offset = self.index;
+        let lead = match self.next_byte()? {
+            Some(b) => b,
+            None => return Err(end_of_input!().at(offset)),
+        };
+
+        let len = match sequence_type(lead) {
+            SequenceType::Single => 1,
+            SequenceType::Pair => 2,
+            SequenceType::Triple => 3,
+            SequenceType::Quad => 4,
+            Unrecognised => return Err(invalid_byte_sequence!().at(offset).with_byte(lead)),
+        };
+
+        self.staging[0] = lead;
+        for i in 1..len {
+            self.staging[i] = match self.next_byte()? {
+                Some(b) => b,
+                // a partial sequence at end-of-input is malformed
+                None => return Err(invalid_byte_sequence!().at(offset).with_byte(lead)),
+            };
+        }
+
+        unsafe {
+            match len {
+                1 => Ok(char::from_u32_unchecked(self.staging[0] as u32)),
+                2 => Ok(char::from_u32_unchecked(decode_pair!(&self.staging))),
+                3 => {
+                    let value = decode_triple!(&self.staging);
+                    if (TRIPLE_EXCLUDED_LOW_BOUND..=TRIPLE_EXCLUDED_HIGH_BOUND).contains(&value) {
+                        Err(decoder_error!(
+                            DecoderErrorCode::InvalidByteSequence,
+                            "value falls within forbidden range [0xd800, 0xdfff]"
+                        )
+                        .at(offset)
+                        .with_byte(lead))
+                    } else {
+                        Ok(char::from_u32_unchecked(value))
+                    }
+                }
+                _ => {
+                    let value = decode_quad!(&self.staging);
+                    if value > QUAD_HIGH_BOUND {
+                        Err(decoder_error!(
+                            DecoderErrorCode::InvalidByteSequence,
+                            "value falls outside maximum bound 0x10ffff"
+                        )
+                        .at(offset)
+                        .with_byte(lead))
+                    } else {
+                        Ok(char::from_u32_unchecked(value))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lossy decode following the Unicode "maximal subpart" substitution rule. A malformed lead
+    /// byte, a truncated sequence, or a decoded value that is a surrogate, overlong, or out of
+    /// range each yields a single [REPLACEMENT_CHARACTER]; decoding then resumes at the next byte.
+    fn decode_next_lossy(&mut self) -> DecoderResult<char> {
+        let lead = match self.next_byte()? {
+            Some(b) => b,
+            None => return Err(end_of_input!()),
+        };
+
+        let len = match sequence_type(lead) {
+            SequenceType::Single => return unsafe { Ok(char::from_u32_unchecked(lead as u32)) },
+            SequenceType::Pair => 2,
+            SequenceType::Triple => 3,
+            SequenceType::Quad => 4,
+            // an invalid lead (including an isolated continuation byte) consumes a single byte
+            Unrecognised => return Ok(REPLACEMENT_CHARACTER),
+        };
+
+        self.staging[0] = lead;
+        for i in 1..len {
+            match self.peek_byte()? {
+                // only consume a byte that is a genuine continuation byte, otherwise the maximal
+                // subpart ends here and the offending byte is left for the next decode
+                Some(b) if is_continuation(b) => {
+                    self.staging[i] = b;
+                    self.next_byte()?;
+                }
+                _ => return Ok(REPLACEMENT_CHARACTER),
+            }
+        }
+
+        unsafe {
+            match len {
+                2 => {
+                    let value = decode_pair!(&self.staging);
+                    if value < PAIR_LOW_BOUND {
+                        Ok(REPLACEMENT_CHARACTER)
+                    } else {
+                        Ok(char::from_u32_unchecked(value))
+                    }
+                }
+                3 => {
+                    let value = decode_triple!(&self.staging);
+                    if value < TRIPLE_LOW_BOUND
+                        || (TRIPLE_EXCLUDED_LOW_BOUND..=TRIPLE_EXCLUDED_HIGH_BOUND).contains(&value)
+                    {
+                        Ok(REPLACEMENT_CHARACTER)
+                    } else {
+                        Ok(char::from_u32_unchecked(value))
+                    }
+                }
+                _ => {
+                    let value = decode_quad!(&self.staging);
+                    if !(QUAD_LOW_BOUND..=QUAD_HIGH_BOUND).contains(&value) {
+                        Ok(REPLACEMENT_CHARACTER)
+                    } else {
+                        Ok(char::from_u32_unchecked(value))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Consume this decoder and return a *fallible* iterator over the stream. Unlike the lenient
+    /// [Iterator] impl (which turns every error into a premature `None`), the returned adaptor
+    /// yields `Ok(c)` for each decoded character, stops cleanly at end-of-input, and propagates a
+    /// genuine [DecoderErrorCode::InvalidByteSequence] or [DecoderErrorCode::StreamFailure] as an
+    /// `Err` item stamped with the byte [index](DecoderError::index) at which it occurred.
+    pub fn results(self) -> Utf8DecoderResults<'a, Buffer> {
+        Utf8DecoderResults {
+            decoder: self,
+            done: false,
         }
     }
 }
 
-impl<'a, B: BufRead> Iterator for Utf8Decoder<'a, B> {
+/// A fallible iterator over a [Utf8Decoder], as produced by [Utf8Decoder::results].
+pub struct Utf8DecoderResults<'a, B: ByteSource> {
+    decoder: Utf8Decoder<'a, B>,
+    /// Set once a terminal error (or end-of-input) has been yielded, after which the iterator is
+    /// fused to `None`
+    done: bool,
+}
+
+impl<'a, B: ByteSource> Iterator for Utf8DecoderResults<'a, B> {
+    type Item = DecoderResult<char>;
+    /// Decode the next character, surfacing real decode errors rather than swallowing them
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.decoder.decode_next() {
+            Ok(c) => Some(Ok(c)),
+            Err(e) => {
+                // a StreamFailure is non-consuming, so without a fuse a persistent I/O error would
+                // be re-polled and re-yielded forever; surface any real error exactly once
+                self.done = true;
+                match e.code {
+                    DecoderErrorCode::EndOfInput => None,
+                    // the decode path already stamped the offending lead byte and its offset; a
+                    // StreamFailure carries no position, so fall back to the current index
+                    DecoderErrorCode::StreamFailure => Some(Err(e.at(self.decoder.index))),
+                    _ => Some(Err(e)),
+                }
+            }
+        }
+    }
+}
+
+impl<'a, B: ByteSource> Iterator for Utf8Decoder<'a, B> {
     type Item = char;
     /// Decode the next character from the underlying stream
     fn next(&mut self) -> Option<Self::Item> {
-        match self.decode_next() {
-            Ok(c) => Some(c),
-            Err(_) => None,
-        }
+        self.decode_next().ok()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use std::fs::File;
     use std::io::BufReader;
@@ -272,4 +554,119 @@ mod tests {
         assert_eq!(decoder.count(), 35283);
         println!("Counted fuzz file in {:?}", start.elapsed());
     }
+
+    fn decode_lossy(buffer: &[u8]) -> String {
+        let mut reader = BufReader::new(buffer);
+        Utf8Decoder::new_lossy(&mut reader).collect()
+    }
+
+    #[test]
+    fn lossy_lead_then_non_continuation_leaves_offending_byte() {
+        // the 2-byte lead is replaced, and the stray ASCII byte is decoded on the next step
+        assert_eq!(decode_lossy(&[0xc3, 0x41]), "\u{fffd}A");
+    }
+
+    #[test]
+    fn lossy_isolated_continuation() {
+        assert_eq!(decode_lossy(&[0x80]), "\u{fffd}");
+    }
+
+    #[test]
+    fn lossy_truncated_sequence_at_eof() {
+        assert_eq!(decode_lossy(&[0xe2, 0x82]), "\u{fffd}");
+    }
+
+    #[test]
+    fn lossy_overlong_encoding() {
+        // overlong two-byte encoding of '/' (U+002F)
+        assert_eq!(decode_lossy(&[0xc0, 0xaf]), "\u{fffd}");
+    }
+
+    #[test]
+    fn lossy_surrogate_range() {
+        // three-byte encoding of U+D800
+        assert_eq!(decode_lossy(&[0xed, 0xa0, 0x80]), "\u{fffd}");
+    }
+
+    #[test]
+    fn lossy_out_of_range_quad() {
+        // four-byte encoding of U+110000, beyond the Unicode maximum
+        assert_eq!(decode_lossy(&[0xf4, 0x90, 0x80, 0x80]), "\u{fffd}");
+    }
+
+    #[test]
+    fn lossy_passes_valid_multibyte_unchanged() {
+        // '£' (U+00A3, two bytes) followed by '€' (U+20AC, three bytes)
+        assert_eq!(decode_lossy(&[0xc2, 0xa3, 0xe2, 0x82, 0xac]), "£€");
+    }
+
+    #[test]
+    fn results_report_offending_byte_and_offset() {
+        use crate::common::DecoderErrorCode;
+        let buffer: &[u8] = &[0x41, 0xff, 0x42];
+        let mut reader = BufReader::new(buffer);
+        let mut results = Utf8Decoder::new(&mut reader).results();
+        assert_eq!(results.next().unwrap().unwrap(), 'A');
+        let err = results.next().unwrap().unwrap_err();
+        assert_eq!(err.code, DecoderErrorCode::InvalidByteSequence);
+        assert_eq!(err.index, 1);
+        assert_eq!(err.byte, Some(0xff));
+        // a terminal error is yielded exactly once, then the iterator is fused
+        assert!(results.next().is_none());
+    }
+
+    #[test]
+    fn peek_does_not_advance() {
+        let buffer: &[u8] = b"abc";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = Utf8Decoder::new(&mut reader);
+        assert_eq!(decoder.peek(), Some('a'));
+        assert_eq!(decoder.peek(), Some('a'));
+        assert_eq!(decoder.decode_next().unwrap(), 'a');
+        assert_eq!(decoder.peek(), Some('b'));
+    }
+
+    #[test]
+    fn try_read_string_consumes_on_match() {
+        let buffer: &[u8] = b"true,";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = Utf8Decoder::new(&mut reader);
+        assert!(decoder.try_read_string("true"));
+        assert_eq!(decoder.decode_next().unwrap(), ',');
+    }
+
+    #[test]
+    fn try_read_string_leaves_stream_untouched_on_mismatch() {
+        let buffer: &[u8] = b"true";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = Utf8Decoder::new(&mut reader);
+        assert!(!decoder.try_read_string("fals"));
+        let rest: String = decoder.collect();
+        assert_eq!(rest, "true");
+    }
+
+    #[test]
+    fn try_read_string_spans_a_refill_boundary() {
+        // a one-byte BufReader forces fill_buf to hand back a single byte at a time, so the match
+        // must survive repeated refills
+        let buffer: &[u8] = b"null";
+        let mut reader = BufReader::with_capacity(1, buffer);
+        let mut decoder = Utf8Decoder::new(&mut reader);
+        assert!(decoder.try_read_string("null"));
+        assert_eq!(decoder.peek(), None);
+    }
+
+    #[test]
+    fn mark_and_rewind_replays_the_region() {
+        let buffer: &[u8] = b"abcdef";
+        let mut reader = BufReader::new(buffer);
+        let mut decoder = Utf8Decoder::new(&mut reader);
+        assert_eq!(decoder.decode_next().unwrap(), 'a');
+        decoder.mark();
+        assert_eq!(decoder.decode_next().unwrap(), 'b');
+        assert_eq!(decoder.decode_next().unwrap(), 'c');
+        decoder.rewind();
+        let rest: String = decoder.collect();
+        assert_eq!(rest, "bcdef");
+    }
 }